@@ -15,18 +15,71 @@
 use crate::errors::{bail, CompilationError, Error};
 use crate::{
     code_block::{CodeBlock, CodeKind},
-    eval_context::EvalCallbacks,
+    eval_context::{EvalCallbacks, TimePassesFormat},
     rust_analyzer::Completions,
     EvalContext, EvalContextOutputs, EvalOutputs,
 };
 use anyhow::Result;
+use std::collections::HashMap;
+
+/// Maximum number of nested alias expansions before we assume an alias refers (directly or
+/// indirectly) to itself and bail out.
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// How evaluation timings are reported. Mirrors rustc's `TimePassesFormat`: a human-readable
+/// summary, or machine-readable JSON for tooling/Jupyter frontends to consume.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimingFormat {
+    Human,
+    Json,
+}
 
 /// A higher level interface to EvalContext. A bit closer to a Repl. Provides commands (start with
 /// ':') that alter context state or print information.
 pub struct CommandContext {
-    print_timings: bool,
+    timing: Option<TimingFormat>,
     eval_context: EvalContext,
     last_errors: Vec<CompilationError>,
+    /// User-defined command aliases, keyed by name without the leading ':'. The value is one or
+    /// more ':'-prefixed command lines that the alias expands to.
+    aliases: HashMap<String, String>,
+    /// How deep we currently are in alias expansion, used to detect recursive aliases.
+    alias_depth: usize,
+    /// Diagnostics the next evaluation is expected to fail with. Populated by `:expect_error` /
+    /// `:expect_compile_fail` and cleared once an evaluation has been checked against them.
+    pending_error_expectations: Vec<ErrorExpectation>,
+    /// Whether to report peak resident set size after each evaluation. The figure is the session
+    /// high-water mark from `rss::peak_rss`, not the peak of that evaluation alone.
+    report_memory: bool,
+}
+
+/// A diagnostic that an evaluation is expected to fail with, inspired by compiletest's
+/// `//~ ERROR E0382` annotations.
+enum ErrorExpectation {
+    /// Any compilation failure satisfies the expectation.
+    AnyError,
+    /// A rustc error code, e.g. `E0382`.
+    Code(String),
+    /// A substring of an error's rendered message.
+    Message(String),
+}
+
+impl ErrorExpectation {
+    fn matches(&self, error: &CompilationError) -> bool {
+        match self {
+            ErrorExpectation::AnyError => true,
+            ErrorExpectation::Code(code) => error.code().as_deref() == Some(code.as_str()),
+            ErrorExpectation::Message(substring) => error.message().contains(substring),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ErrorExpectation::AnyError => "a compilation error".to_owned(),
+            ErrorExpectation::Code(code) => code.clone(),
+            ErrorExpectation::Message(substring) => format!("{:?}", substring),
+        }
+    }
 }
 
 impl CommandContext {
@@ -38,9 +91,13 @@ impl CommandContext {
 
     pub fn with_eval_context(eval_context: EvalContext) -> CommandContext {
         CommandContext {
-            print_timings: false,
+            timing: None,
             eval_context,
             last_errors: Vec::new(),
+            aliases: HashMap::new(),
+            alias_depth: 0,
+            pending_error_expectations: Vec::new(),
+            report_memory: false,
         }
     }
 
@@ -70,26 +127,84 @@ impl CommandContext {
                 non_command_code = non_command_code.with_segment(segment);
             }
         }
-        let result = if non_command_code.is_empty() {
-            Ok(EvalOutputs::new())
-        } else {
+        let commands_duration = start.elapsed();
+        let did_eval = !non_command_code.is_empty();
+        let eval_start = Instant::now();
+        let result = if did_eval {
             self.eval_context
                 .eval_with_callbacks(non_command_code, callbacks)
+        } else {
+            Ok(EvalOutputs::new())
         };
+        let eval_duration = eval_start.elapsed();
         let duration = start.elapsed();
         match result {
             Ok(m) => {
+                // If an evaluation ran but we were told to expect a failure, the cell is wrong.
+                if did_eval && !self.pending_error_expectations.is_empty() {
+                    let expectations = std::mem::take(&mut self.pending_error_expectations);
+                    bail!(
+                        "Expected {} but the code compiled successfully",
+                        describe_expectations(&expectations)
+                    );
+                }
                 eval_outputs.merge(m);
-                if self.print_timings {
+                if did_eval && self.report_memory {
+                    if let Some(bytes) = rss::peak_rss() {
+                        eval_outputs.peak_memory_bytes = Some(bytes);
+                        eval_outputs
+                            .content_by_mime_type
+                            .entry("text/plain".to_owned())
+                            .or_default()
+                            .push_str(&format!(
+                                "Peak memory (RSS, session high-water mark): {}\n",
+                                format_bytes(bytes)
+                            ));
+                    }
+                }
+                if let Some(format) = self.timing {
                     eval_outputs.timing = Some(duration);
+                    if format == TimingFormat::Json {
+                        // Surface the timing breakdown as `application/json` so standard
+                        // Jupyter/tooling frontends pick it up. Use `or_insert_with` so we never
+                        // clobber an `application/json` display bundle the cell produced itself; in
+                        // that case the timing is still available via the `timing` field.
+                        eval_outputs
+                            .content_by_mime_type
+                            .entry("application/json".to_owned())
+                            .or_insert_with(|| {
+                                timing_json(duration, commands_duration, eval_duration)
+                            });
+                    }
                 }
                 Ok(eval_outputs)
             }
             Err(Error::CompilationErrors(errors)) => {
                 self.last_errors = errors.clone();
+                // If the failure was expected, swallow it and report success instead.
+                if !self.pending_error_expectations.is_empty() {
+                    let expectations = std::mem::take(&mut self.pending_error_expectations);
+                    if let Some(unmet) = first_unmet_expectation(&expectations, &errors) {
+                        bail!(
+                            "Expected {} but got {}",
+                            unmet.describe(),
+                            summarize_errors(&errors)
+                        );
+                    }
+                    eval_outputs.merge(expected_error_output(&expectations));
+                    return Ok(eval_outputs);
+                }
                 Err(Error::CompilationErrors(errors))
             }
-            x => x,
+            other => {
+                // Any other failure (e.g. a subprocess error) is not a compilation diagnostic, so
+                // it can't satisfy an expectation. Drop any pending expectations rather than let
+                // them leak into the next, unrelated evaluation.
+                if did_eval {
+                    self.pending_error_expectations.clear();
+                }
+                other
+            }
         }
     }
 
@@ -145,6 +260,12 @@ impl CommandContext {
         command: &str,
         args: &Option<String>,
     ) -> Result<EvalOutputs, Error> {
+        // Aliases are resolved before dispatching to a builtin, the same way cargo resolves
+        // `[alias]` entries before its builtin subcommands. An alias can never shadow a builtin
+        // because `:alias` refuses to define one whose name collides.
+        if let Some(expansion) = self.aliases.get(command.trim_start_matches(':')).cloned() {
+            return self.expand_alias(&expansion);
+        }
         match command {
             ":internal_debug" => {
                 let debug_mode = !self.eval_context.debug_mode();
@@ -152,6 +273,8 @@ impl CommandContext {
                 text_output(format!("Internals debugging: {}", debug_mode))
             }
             ":load_config" => self.load_config(),
+            ":alias" => self.process_alias_command(args),
+            ":unalias" => self.process_unalias_command(args),
             ":version" => text_output(env!("CARGO_PKG_VERSION")),
             ":vars" => {
                 let mut outputs = EvalOutputs::new();
@@ -173,6 +296,27 @@ impl CommandContext {
             }
             ":clear" => self.eval_context.clear().map(|_| EvalOutputs::new()),
             ":dep" => self.process_dep_command(args),
+            ":expect_error" => {
+                let arg = match args {
+                    Some(a) if !a.trim().is_empty() => a.trim(),
+                    _ => bail!(":expect_error requires an error code or message substring"),
+                };
+                self.pending_error_expectations
+                    .push(parse_error_expectation(arg));
+                text_output(format!("Next evaluation is expected to fail with {}", arg))
+            }
+            ":expect_compile_fail" => {
+                let expectation = match args {
+                    Some(a) if !a.trim().is_empty() => parse_error_expectation(a.trim()),
+                    _ => ErrorExpectation::AnyError,
+                };
+                let description = expectation.describe();
+                self.pending_error_expectations.push(expectation);
+                text_output(format!(
+                    "Next evaluation is expected to fail compilation with {}",
+                    description
+                ))
+            }
             ":last_compile_dir" => {
                 text_output(format!("{:?}", self.eval_context.last_compile_dir()))
             }
@@ -207,13 +351,54 @@ impl CommandContext {
             }
             ":quit" => std::process::exit(0),
             ":timing" => {
-                self.print_timings = !self.print_timings;
-                text_output(format!("Timing: {}", self.print_timings))
+                self.timing = match args.as_ref().map(|s| s.trim()) {
+                    Some("json") => Some(TimingFormat::Json),
+                    Some("off") => None,
+                    Some("") | None => {
+                        // Bare `:timing` toggles human-readable timing, as it always has.
+                        if self.timing.is_none() {
+                            Some(TimingFormat::Human)
+                        } else {
+                            None
+                        }
+                    }
+                    Some(other) => {
+                        bail!("Unknown :timing format {:?} (expected 'json' or 'off')", other)
+                    }
+                };
+                text_output(format!("Timing: {}", describe_timing(self.timing)))
             }
             ":time_passes" => {
-                self.eval_context
-                    .set_time_passes(!self.eval_context.time_passes());
-                text_output(format!("Time passes: {}", self.eval_context.time_passes()))
+                match args.as_ref().map(|s| s.trim()) {
+                    Some("json") => {
+                        self.eval_context
+                            .set_time_passes_format(TimePassesFormat::Json);
+                    }
+                    Some("off") => {
+                        self.eval_context
+                            .set_time_passes_format(TimePassesFormat::None);
+                    }
+                    Some("") | None => {
+                        // Bare `:time_passes` toggles rustc's human-readable pass printing.
+                        let format = if self.eval_context.time_passes_format() == TimePassesFormat::None {
+                            TimePassesFormat::Text
+                        } else {
+                            TimePassesFormat::None
+                        };
+                        self.eval_context.set_time_passes_format(format);
+                    }
+                    Some(other) => {
+                        bail!("Unknown :time_passes format {:?} (expected 'json' or 'off')", other)
+                    }
+                }
+                text_output(format!(
+                    "Time passes: {}",
+                    describe_time_passes(self.eval_context.time_passes_format())
+                ))
+            }
+            ":memory" => {
+                self.report_memory = !self.report_memory;
+                text_output(format!("Memory reporting: {}", self.report_memory))
             }
             ":sccache" => {
                 self.eval_context
@@ -258,6 +443,10 @@ impl CommandContext {
                  :explain          Print explanation of last error\n\
                  :clear            Clear all state, keeping compilation cache\n\
                  :dep              Add dependency. e.g. :dep regex = \"1.0\"\n\
+                 :alias [name cmd] Define/list command aliases. e.g. :alias p :dep plotters = \"0.3\"\n\
+                 :unalias [name]   Remove an alias (or all aliases)\n\
+                 :expect_error <code|msg>  Assert the next evaluation fails with this diagnostic\n\
+                 :expect_compile_fail [..]  Assert the next evaluation fails to compile\n\
                  :sccache [0|1]    Set whether to use sccache.\n\
                  :linker [linker]  Set/print linker. Supported: system, lld\n\
                  :version          Print Evcxr version\n\
@@ -265,9 +454,10 @@ impl CommandContext {
                  :preserve_vars_on_panic [0|1]  Try to keep vars on panic\n\n\
                  Mostly for development / debugging purposes:\n\
                  :last_compile_dir Print the directory in which we last compiled\n\
-                 :timing           Toggle printing of how long evaluations take\n\
+                 :timing [json]    Toggle/set reporting of how long evaluations take\n\
+                 :memory           Toggle reporting of session peak memory (RSS) after each evaluation\n\
                  :last_error_json  Print the last compilation error as JSON (for debugging)\n\
-                 :time_passes      Toggle printing of rustc pass times (requires nightly)\n\
+                 :time_passes [json]  Toggle/set printing of rustc pass times (requires nightly)\n\
                  :internal_debug   Toggle various internal debugging code",
             ),
             _ => bail!("Unrecognised command {}", command),
@@ -299,6 +489,88 @@ impl CommandContext {
         out
     }
 
+    /// Handles `:alias`. With no arguments it lists current definitions; with `name expansion` it
+    /// defines (or redefines) an alias.
+    fn process_alias_command(&mut self, args: &Option<String>) -> Result<EvalOutputs, Error> {
+        let args = args.as_ref().map(|a| a.trim()).unwrap_or("");
+        if args.is_empty() {
+            return self.list_aliases();
+        }
+        let mut parts = args.splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap();
+        let expansion = parts.next().map(str::trim).unwrap_or("");
+        if expansion.is_empty() {
+            bail!("Usage: :alias name :command [args]");
+        }
+        let name = name.trim_start_matches(':');
+        if name.is_empty() {
+            bail!("Alias name must not be empty");
+        }
+        if is_builtin_command(name) {
+            bail!("Cannot define alias {:?}: it collides with a built-in command", name);
+        }
+        self.aliases.insert(name.to_owned(), expansion.to_owned());
+        text_output(format!(":{} => {}", name, expansion))
+    }
+
+    /// Handles `:unalias`. With no arguments it removes every alias; with a name it removes that
+    /// one, erroring if it was not defined.
+    fn process_unalias_command(&mut self, args: &Option<String>) -> Result<EvalOutputs, Error> {
+        match args.as_ref().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            None => {
+                self.aliases.clear();
+                text_output("Removed all aliases")
+            }
+            Some(name) => {
+                let name = name.trim_start_matches(':');
+                if self.aliases.remove(name).is_none() {
+                    bail!("No such alias: {}", name);
+                }
+                text_output(format!("Removed alias :{}", name))
+            }
+        }
+    }
+
+    fn list_aliases(&self) -> Result<EvalOutputs, Error> {
+        if self.aliases.is_empty() {
+            return text_output("No aliases defined");
+        }
+        let mut names: Vec<&String> = self.aliases.keys().collect();
+        names.sort();
+        let mut out = String::new();
+        for name in names {
+            out.push_str(&format!(":{} => {}\n", name, self.aliases[name]));
+        }
+        // Trim the trailing newline; `text_output` adds one back.
+        out.pop();
+        text_output(out)
+    }
+
+    /// Expands an alias into its constituent ':'-prefixed command lines and executes each, guarding
+    /// against aliases that expand (directly or indirectly) to themselves.
+    fn expand_alias(&mut self, expansion: &str) -> Result<EvalOutputs, Error> {
+        if self.alias_depth >= MAX_ALIAS_DEPTH {
+            bail!("Alias expansion too deep - is the alias recursive?");
+        }
+        self.alias_depth += 1;
+        let mut outputs = EvalOutputs::new();
+        for line in expansion.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match self.execute(line) {
+                Ok(o) => outputs.merge(o),
+                Err(e) => {
+                    self.alias_depth -= 1;
+                    return Err(e);
+                }
+            }
+        }
+        self.alias_depth -= 1;
+        Ok(outputs)
+    }
+
     fn process_dep_command(&mut self, args: &Option<String>) -> Result<EvalOutputs, Error> {
         use regex::Regex;
         let args = if let Some(v) = args {
@@ -306,21 +578,310 @@ impl CommandContext {
         } else {
             bail!(":dep requires arguments")
         };
+        // A dependency may be scoped to a target with a leading `cfg(...)` predicate, e.g.
+        // `:dep cfg(target_os = "linux") winit = "0.29"`. Peel it off before parsing name = spec.
+        let (target, rest) = split_cfg_prefix(args)?;
         lazy_static! {
             static ref DEP_RE: Regex = Regex::new("^([^= ]+) *(= *(.+))?$").unwrap();
         }
-        if let Some(captures) = DEP_RE.captures(args) {
+        if let Some(captures) = DEP_RE.captures(rest.trim()) {
             self.eval_context.add_dep(
                 &captures[1],
                 &captures.get(3).map_or("\"*\"", |m| m.as_str()),
+                target.as_deref(),
             )?;
             Ok(EvalOutputs::new())
         } else {
-            bail!("Invalid :dep command. Expected: name = ... or just name");
+            bail!("Invalid :dep command. Expected: [cfg(...)] name = ... or just name");
         }
     }
 }
 
+/// Peels an optional leading `cfg(...)` predicate off a `:dep` argument string. Returns the
+/// normalized `cfg(...)` target key (if present) and the remaining `name = spec` text.
+fn split_cfg_prefix(args: &str) -> Result<(Option<String>, String), Error> {
+    let trimmed = args.trim_start();
+    if !trimmed.starts_with("cfg(") {
+        return Ok((None, args.to_owned()));
+    }
+    // Scan from the opening paren to its match, ignoring parens inside string literals.
+    let open = trimmed.find('(').unwrap();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut end = None;
+    for (i, ch) in trimmed.char_indices().skip(open) {
+        match ch {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let end = match end {
+        Some(e) => e,
+        None => bail!("Unbalanced parentheses in cfg(...) expression"),
+    };
+    let inner = &trimmed[open + 1..end];
+    let normalized = parse_cfg_expression(inner)?;
+    let rest = trimmed[end + 1..].trim_start().to_owned();
+    Ok((Some(format!("cfg({})", normalized)), rest))
+}
+
+/// Validates a `cfg(...)` predicate, returning it in a normalized form. Supports the predicates
+/// `name` and `name = "value"` combined with `all(...)`, `any(...)` and `not(...)` nested
+/// arbitrarily, matching the grammar cargo accepts for target-scoped dependencies.
+fn parse_cfg_expression(input: &str) -> Result<String, Error> {
+    let mut parser = CfgParser {
+        input: input.as_bytes(),
+        pos: 0,
+    };
+    let expr = parser.parse_expr()?;
+    parser.skip_ws();
+    if parser.pos != parser.input.len() {
+        bail!(
+            "Unexpected trailing input in cfg expression: {:?}",
+            String::from_utf8_lossy(&parser.input[parser.pos..])
+        );
+    }
+    Ok(expr)
+}
+
+struct CfgParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl CfgParser<'_> {
+    fn parse_expr(&mut self) -> Result<String, Error> {
+        self.skip_ws();
+        let ident = self.parse_ident()?;
+        match ident.as_str() {
+            "all" | "any" | "not" => {
+                self.expect(b'(')?;
+                let mut parts = Vec::new();
+                loop {
+                    parts.push(self.parse_expr()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(b',') => {
+                            self.pos += 1;
+                            self.skip_ws();
+                            if self.peek() == Some(b')') {
+                                break;
+                            }
+                        }
+                        Some(b')') => break,
+                        other => bail!(
+                            "Expected ',' or ')' in cfg expression, found {:?}",
+                            other.map(|c| c as char)
+                        ),
+                    }
+                }
+                self.expect(b')')?;
+                if ident == "not" && parts.len() != 1 {
+                    bail!("cfg not(...) takes exactly one predicate");
+                }
+                Ok(format!("{}({})", ident, parts.join(", ")))
+            }
+            _ => {
+                self.skip_ws();
+                if self.peek() == Some(b'=') {
+                    self.pos += 1;
+                    self.skip_ws();
+                    let value = self.parse_string()?;
+                    Ok(format!("{} = \"{}\"", ident, value))
+                } else {
+                    Ok(ident)
+                }
+            }
+        }
+    }
+
+    fn parse_ident(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'_' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            bail!("Expected an identifier in cfg expression");
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == b'"' {
+                let value = String::from_utf8_lossy(&self.input[start..self.pos]).into_owned();
+                self.pos += 1;
+                return Ok(value);
+            }
+            self.pos += 1;
+        }
+        bail!("Unterminated string literal in cfg expression");
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), Error> {
+        self.skip_ws();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            bail!(
+                "Expected {:?} in cfg expression",
+                expected as char
+            );
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// The names (without leading ':') of every built-in command. Used to stop an alias from shadowing
+/// a builtin.
+const BUILTIN_COMMANDS: &[&str] = &[
+    "internal_debug",
+    "load_config",
+    "alias",
+    "unalias",
+    "version",
+    "vars",
+    "preserve_vars_on_panic",
+    "clear",
+    "dep",
+    "expect_error",
+    "expect_compile_fail",
+    "last_compile_dir",
+    "opt",
+    "fmt",
+    "efmt",
+    "quit",
+    "timing",
+    "time_passes",
+    "memory",
+    "sccache",
+    "linker",
+    "explain",
+    "last_error_json",
+    "help",
+];
+
+fn is_builtin_command(name: &str) -> bool {
+    BUILTIN_COMMANDS.contains(&name)
+}
+
+/// Interprets a `:expect_error` argument as a rustc error code (e.g. `E0382`) when it looks like
+/// one, otherwise as a message substring.
+fn parse_error_expectation(arg: &str) -> ErrorExpectation {
+    use regex::Regex;
+    lazy_static! {
+        static ref CODE_RE: Regex = Regex::new("^E[0-9]{4}$").unwrap();
+    }
+    if CODE_RE.is_match(arg) {
+        ErrorExpectation::Code(arg.to_owned())
+    } else {
+        ErrorExpectation::Message(arg.to_owned())
+    }
+}
+
+/// Returns the first expectation not satisfied by any of `errors`, if any.
+fn first_unmet_expectation<'a>(
+    expectations: &'a [ErrorExpectation],
+    errors: &[CompilationError],
+) -> Option<&'a ErrorExpectation> {
+    expectations
+        .iter()
+        .find(|exp| !errors.iter().any(|error| exp.matches(error)))
+}
+
+fn describe_expectations(expectations: &[ErrorExpectation]) -> String {
+    expectations
+        .iter()
+        .map(ErrorExpectation::describe)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn summarize_errors(errors: &[CompilationError]) -> String {
+    if errors.is_empty() {
+        return "no errors".to_owned();
+    }
+    errors
+        .iter()
+        .map(|error| match error.code() {
+            Some(code) => format!("{}: {}", code, error.message()),
+            None => error.message(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn expected_error_output(expectations: &[ErrorExpectation]) -> EvalOutputs {
+    let mut outputs = EvalOutputs::new();
+    outputs.content_by_mime_type.insert(
+        "text/plain".to_owned(),
+        format!(
+            "Evaluation failed as expected with {}\n",
+            describe_expectations(expectations)
+        ),
+    );
+    outputs
+}
+
+fn describe_timing(format: Option<TimingFormat>) -> &'static str {
+    match format {
+        None => "off",
+        Some(TimingFormat::Human) => "on",
+        Some(TimingFormat::Json) => "json",
+    }
+}
+
+fn describe_time_passes(format: TimePassesFormat) -> &'static str {
+    match format {
+        TimePassesFormat::None => "off",
+        TimePassesFormat::Text => "on",
+        TimePassesFormat::Json => "json",
+    }
+}
+
+/// Renders the wall-clock breakdown of an evaluation as JSON, following rustc's convention of
+/// emitting pass times as structured data that tooling can consume.
+fn timing_json(
+    total: std::time::Duration,
+    command_processing: std::time::Duration,
+    eval: std::time::Duration,
+) -> String {
+    format!(
+        "{{\"total_ms\":{},\"command_processing_ms\":{},\"eval_ms\":{}}}",
+        total.as_millis(),
+        command_processing.as_millis(),
+        eval.as_millis()
+    )
+}
+
 fn html_escape(input: &str, out: &mut String) {
     for ch in input.chars() {
         match ch {
@@ -331,6 +892,72 @@ fn html_escape(input: &str, out: &mut String) {
     }
 }
 
+/// Formats a byte count using binary units (KiB/MiB/GiB) for display.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Cross-platform sampling of peak resident set size, matching how rustc's driver reports process
+/// memory via `get_resident_set_size`.
+///
+/// On Unix [`peak_rss`] returns `getrusage(RUSAGE_CHILDREN).ru_maxrss`, the peak RSS of the child
+/// compile/execution processes (cargo/rustc and the cell runner). Note this is a **session-lifetime
+/// high-water mark**: it is the largest RSS of any child waited for so far and never decreases, so
+/// it reflects the most memory-hungry cell in the session rather than the most recent one. On
+/// Windows there is no cheap child-RSS query, so we fall back to the evcxr process's own peak
+/// working set, which *excludes* the child compile/execution processes.
+mod rss {
+    /// Peak child RSS in bytes (a session high-water mark), or `None` if unavailable.
+    #[cfg(unix)]
+    pub(super) fn peak_rss() -> Option<u64> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+            return None;
+        }
+        Some(maxrss_to_bytes(usage.ru_maxrss as u64))
+    }
+
+    // `ru_maxrss` is in kilobytes on Linux but in bytes on macOS / BSD.
+    #[cfg(target_os = "linux")]
+    fn maxrss_to_bytes(maxrss: u64) -> u64 {
+        maxrss * 1024
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn maxrss_to_bytes(maxrss: u64) -> u64 {
+        maxrss
+    }
+
+    /// Windows fallback: the evcxr process's own peak working set (children excluded).
+    #[cfg(windows)]
+    pub(super) fn peak_rss() -> Option<u64> {
+        use winapi::um::processthreadsapi::GetCurrentProcess;
+        use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+        let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { std::mem::zeroed() };
+        counters.cb = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+        if unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, counters.cb) } != 0 {
+            Some(counters.PeakWorkingSetSize as u64)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub(super) fn peak_rss() -> Option<u64> {
+        None
+    }
+}
+
 fn text_output<T: Into<String>>(text: T) -> Result<EvalOutputs, Error> {
     let mut outputs = EvalOutputs::new();
     let mut content = text.into();
@@ -340,3 +967,150 @@ fn text_output<T: Into<String>>(text: T) -> Result<EvalOutputs, Error> {
         .insert("text/plain".to_owned(), content);
     Ok(outputs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_code_is_parsed_as_a_code() {
+        match parse_error_expectation("E0382") {
+            ErrorExpectation::Code(code) => assert_eq!(code, "E0382"),
+            _ => panic!("expected a code expectation"),
+        }
+    }
+
+    #[test]
+    fn alias_define_expand_and_unalias_round_trip() {
+        let (mut ctx, _) = CommandContext::new_for_testing();
+        ctx.execute(":alias ver :version").unwrap();
+        // Listing shows the definition.
+        let listed = ctx.execute(":alias").unwrap();
+        assert!(listed.content_by_mime_type[&"text/plain".to_owned()].contains(":ver => :version"));
+        // Expanding the alias runs the underlying builtin.
+        let expanded = ctx.execute(":ver").unwrap();
+        assert_eq!(
+            expanded.content_by_mime_type[&"text/plain".to_owned()].trim(),
+            env!("CARGO_PKG_VERSION")
+        );
+        // After removal the alias is gone.
+        ctx.execute(":unalias ver").unwrap();
+        assert!(ctx.execute(":ver").is_err());
+    }
+
+    #[test]
+    fn alias_defining_over_a_builtin_is_rejected() {
+        let (mut ctx, _) = CommandContext::new_for_testing();
+        assert!(ctx.execute(":alias dep :version").is_err());
+    }
+
+    #[test]
+    fn recursive_alias_bails_instead_of_looping() {
+        let (mut ctx, _) = CommandContext::new_for_testing();
+        ctx.execute(":alias a :a").unwrap();
+        let err = ctx.execute(":a").unwrap_err();
+        assert!(format!("{}", err).contains("too deep"));
+    }
+
+    #[test]
+    fn builtin_commands_are_detected_for_alias_collisions() {
+        assert!(is_builtin_command("dep"));
+        assert!(is_builtin_command("alias"));
+        assert!(is_builtin_command("memory"));
+        assert!(!is_builtin_command("plot"));
+        // The leading ':' is not part of a stored alias name.
+        assert!(!is_builtin_command(":dep"));
+    }
+
+    #[test]
+    fn cfg_parses_bare_and_keyed_predicates() {
+        assert_eq!(parse_cfg_expression("unix").unwrap(), "unix");
+        assert_eq!(
+            parse_cfg_expression("target_os = \"linux\"").unwrap(),
+            "target_os = \"linux\""
+        );
+    }
+
+    #[test]
+    fn cfg_parses_nested_combinators_and_normalizes_spacing() {
+        assert_eq!(
+            parse_cfg_expression("all(unix,not(target_os=\"macos\"))").unwrap(),
+            "all(unix, not(target_os = \"macos\"))"
+        );
+        assert_eq!(
+            parse_cfg_expression("any( target_arch=\"x86_64\" , target_arch=\"aarch64\" )").unwrap(),
+            "any(target_arch = \"x86_64\", target_arch = \"aarch64\")"
+        );
+    }
+
+    #[test]
+    fn cfg_rejects_malformed_expressions() {
+        assert!(parse_cfg_expression("all(unix").is_err());
+        assert!(parse_cfg_expression("not(unix, windows)").is_err());
+        assert!(parse_cfg_expression("target_os = linux").is_err());
+        assert!(parse_cfg_expression("unix extra").is_err());
+    }
+
+    #[test]
+    fn split_cfg_prefix_peels_target_and_leaves_spec() {
+        let (target, rest) =
+            split_cfg_prefix("cfg(target_os = \"linux\") winit = \"0.29\"").unwrap();
+        assert_eq!(target.as_deref(), Some("cfg(target_os = \"linux\")"));
+        assert_eq!(rest.trim(), "winit = \"0.29\"");
+
+        let (target, rest) = split_cfg_prefix("regex = \"1.0\"").unwrap();
+        assert_eq!(target, None);
+        assert_eq!(rest, "regex = \"1.0\"");
+    }
+
+    #[test]
+    fn split_cfg_prefix_rejects_unbalanced_parens() {
+        assert!(split_cfg_prefix("cfg(all(unix) winit = \"0.29\"").is_err());
+    }
+
+    #[test]
+    fn format_bytes_uses_binary_units() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(1024 * 1024), "1.0 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn timing_json_breaks_down_phases() {
+        use std::time::Duration;
+        let json = timing_json(
+            Duration::from_millis(30),
+            Duration::from_millis(5),
+            Duration::from_millis(25),
+        );
+        assert_eq!(
+            json,
+            "{\"total_ms\":30,\"command_processing_ms\":5,\"eval_ms\":25}"
+        );
+        // The breakdown is a single JSON object, as `application/json` consumers expect.
+        assert!(json.starts_with('{') && json.ends_with('}'));
+    }
+
+    #[test]
+    fn describe_timing_renders_each_mode() {
+        assert_eq!(describe_timing(None), "off");
+        assert_eq!(describe_timing(Some(TimingFormat::Human)), "on");
+        assert_eq!(describe_timing(Some(TimingFormat::Json)), "json");
+    }
+
+    #[test]
+    fn non_code_is_parsed_as_a_message() {
+        match parse_error_expectation("borrow of moved value") {
+            ErrorExpectation::Message(msg) => assert_eq!(msg, "borrow of moved value"),
+            _ => panic!("expected a message expectation"),
+        }
+        // A code-looking string that isn't exactly E + 4 digits is a message.
+        match parse_error_expectation("E12") {
+            ErrorExpectation::Message(msg) => assert_eq!(msg, "E12"),
+            _ => panic!("expected a message expectation"),
+        }
+    }
+}